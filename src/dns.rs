@@ -1,28 +1,40 @@
 use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::pin::Pin;
+use std::slice;
 use std::str;
+#[cfg(feature = "dns-over-tls")]
+use std::sync::{Arc, OnceLock};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use futures_core::Stream;
 use futures_util::{StreamExt, future, ready, stream};
 use hickory_proto::{
     ProtoError, ProtoErrorKind,
-    op::Query,
-    rr::{DNSClass, Name, RData, RecordType},
+    op::{Query, ResponseCode},
+    rr::{DNSClass, Name, RData, Record, RecordType},
+    tcp::TcpClientStream,
     udp::UdpClientStream,
-    xfer::{DnsHandle, DnsRequestOptions, DnsResponse},
+    xfer::{DnsHandle, DnsRequestOptions, DnsResponse, Protocol},
 };
 use pin_project_lite::pin_project;
+use thiserror::Error;
 use tracing::trace_span;
 use tracing_futures::Instrument;
 
 #[cfg(feature = "tokio-dns-resolver")]
-use hickory_client::client::Client;
+use hickory_client::client::Client as DnsClient;
 #[cfg(feature = "tokio-dns-resolver")]
 use hickory_proto::runtime::TokioRuntimeProvider;
 #[cfg(feature = "tokio-dns-resolver")]
-use tokio::runtime::Handle;
+use tokio::{runtime::Handle, task::JoinHandle};
+
+#[cfg(feature = "dns-over-tls")]
+use hickory_proto::rustls::tls_client_connect;
 
 use crate::{Resolutions, Version};
 
@@ -31,6 +43,9 @@ use crate::{Resolutions, Version};
 
 const DEFAULT_DNS_PORT: u16 = 53;
 
+#[cfg(feature = "dns-over-tls")]
+const DEFAULT_DNS_TLS_PORT: u16 = 853;
+
 /// All builtin DNS resolvers.
 pub const ALL: &dyn crate::Resolver<'static> = &&[
     #[cfg(feature = "opendns")]
@@ -154,11 +169,101 @@ pub const CLOUDFLARE_V6: &dyn crate::Resolver<'static> = &Resolver::new_static(
     DNSClass::CH,
 );
 
+/// Combined Cloudflare DNS-over-TLS IPv4 and IPv6 options.
+#[cfg(all(feature = "cloudflare", feature = "dns-over-tls"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "cloudflare", feature = "dns-over-tls")))
+)]
+pub const CLOUDFLARE_TLS: &dyn crate::Resolver<'static> = &&[CLOUDFLARE_TLS_V4, CLOUDFLARE_TLS_V6];
+
+/// Cloudflare DNS-over-TLS IPv4 DNS resolver options.
+#[cfg(all(feature = "cloudflare", feature = "dns-over-tls"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "cloudflare", feature = "dns-over-tls")))
+)]
+pub const CLOUDFLARE_TLS_V4: &dyn crate::Resolver<'static> = &Resolver::new_static(
+    "whoami.cloudflare",
+    &[
+        IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+        IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)),
+    ],
+    DEFAULT_DNS_TLS_PORT,
+    QueryMethod::TXT,
+    DNSClass::CH,
+)
+.with_transport(Transport::Tls("cloudflare-dns.com"));
+
+/// Cloudflare DNS-over-TLS IPv6 DNS resolver options.
+#[cfg(all(feature = "cloudflare", feature = "dns-over-tls"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "cloudflare", feature = "dns-over-tls")))
+)]
+pub const CLOUDFLARE_TLS_V6: &dyn crate::Resolver<'static> = &Resolver::new_static(
+    "whoami.cloudflare",
+    &[
+        // 2606:4700:4700::1111
+        IpAddr::V6(Ipv6Addr::new(9734, 18176, 18176, 0, 0, 0, 0, 4369)),
+        // 2606:4700:4700::1001
+        IpAddr::V6(Ipv6Addr::new(9734, 18176, 18176, 0, 0, 0, 0, 4097)),
+    ],
+    DEFAULT_DNS_TLS_PORT,
+    QueryMethod::TXT,
+    DNSClass::CH,
+)
+.with_transport(Transport::Tls("cloudflare-dns.com"));
+
+/// Google DNS resolver options for the system's recursive DNS resolver.
+///
+/// Rather than our own IP address, this resolves the IP address the
+/// recursive DNS resolver configured on the system uses to query Google's
+/// authoritative servers. The system's resolver that was queried is
+/// available through [`Details::server`].
+#[cfg(all(feature = "google", feature = "system-config"))]
+#[cfg_attr(docsrs, doc(cfg(all(feature = "google", feature = "system-config"))))]
+pub const GOOGLE_RECURSIVE: &dyn crate::Resolver<'static> = &Resolver::new_static(
+    "o-o.myaddr.l.google.com",
+    &[],
+    DEFAULT_DNS_PORT,
+    QueryMethod::TXT,
+    DNSClass::IN,
+)
+.with_system_servers(true);
+
 ////////////////////////////////////////////////////////////////////////////////
 // Error
 
 /// DNS resolver error.
-pub type Error = ProtoError;
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum Error {
+    /// DNS protocol error.
+    #[error("{0}")]
+    Proto(ProtoError),
+    /// The server responded with an error response code.
+    #[error("unexpected response code: {0}")]
+    ResponseCode(ResponseCode),
+    /// The question section of the response does not match the query.
+    #[error("response question does not match query")]
+    QueryMismatch,
+    /// An answer's owner name does not match the queried name.
+    #[error("answer for unexpected name: {0}")]
+    UnexpectedName(Box<Name>),
+    /// An answer's record type does not match the queried type.
+    #[error("answer of unexpected type: {0}")]
+    UnexpectedType(RecordType),
+    /// An answer's class does not match the queried class.
+    #[error("answer of unexpected class: {0}")]
+    UnexpectedClass(DNSClass),
+}
+
+impl From<ProtoError> for Error {
+    fn from(error: ProtoError) -> Self {
+        Self::Proto(error)
+    }
+}
 
 ////////////////////////////////////////////////////////////////////////////////
 // Details & options
@@ -169,6 +274,12 @@ pub struct Details {
     name: Name,
     server: SocketAddr,
     method: QueryMethod,
+    protocol: Protocol,
+    latency: Duration,
+    response_code: ResponseCode,
+    authoritative: bool,
+    recursion_available: bool,
+    answers: Vec<Record>,
 }
 
 impl Details {
@@ -189,6 +300,69 @@ impl Details {
     pub fn query_method(&self) -> QueryMethod {
         self.method
     }
+
+    /// The protocol used to query the DNS server.
+    #[must_use]
+    pub fn protocol(&self) -> Protocol {
+        self.protocol
+    }
+
+    /// Round-trip time of the query that produced the response.
+    #[must_use]
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Response code of the DNS response.
+    #[must_use]
+    pub fn response_code(&self) -> ResponseCode {
+        self.response_code
+    }
+
+    /// Whether the response was marked as authoritative (`AA` flag).
+    #[must_use]
+    pub fn authoritative(&self) -> bool {
+        self.authoritative
+    }
+
+    /// Whether the server advertised recursion as available (`RA` flag).
+    #[must_use]
+    pub fn recursion_available(&self) -> bool {
+        self.recursion_available
+    }
+
+    /// Lowest TTL of the answer records, for how long the resolution may be
+    /// cached.
+    #[must_use]
+    pub fn ttl(&self) -> u32 {
+        self.answers.iter().map(Record::ttl).min().unwrap_or(0)
+    }
+
+    /// All answer records within the DNS response.
+    #[must_use]
+    pub fn answers(&self) -> &[Record] {
+        &self.answers
+    }
+
+    /// All character-strings of the `TXT` answer records within the DNS
+    /// response, lossily decoded as UTF-8.
+    pub fn txt(&self) -> impl Iterator<Item = Cow<'_, str>> + '_ {
+        txt_strings(&self.answers).map(String::from_utf8_lossy)
+    }
+
+    /// The EDNS client subnet reported back by the server, if any.
+    ///
+    /// Google reports the client subnet it received as a `TXT` string in the
+    /// form `edns0-client-subnet 203.0.113.0/24`, which is returned here as
+    /// the prefix address and length.
+    #[must_use]
+    pub fn edns_client_subnet(&self) -> Option<(IpAddr, u8)> {
+        self.txt().find_map(|txt| {
+            let subnet = txt.strip_prefix("edns0-client-subnet ")?;
+            let (addr, len) = subnet.trim().split_once('/')?;
+            Some((addr.parse().ok()?, len.parse().ok()?))
+        })
+    }
 }
 
 /// Method used to query an IP address from a DNS server
@@ -199,10 +373,154 @@ pub enum QueryMethod {
     A,
     /// The first queried `AAAA` name record is extracted as our IP address.
     AAAA,
-    /// The first `TXT` record is extracted and parsed as our IP address.
+    /// The first `TXT` character-string that is a valid IP address is
+    /// extracted as our IP address.
     TXT,
 }
 
+impl Extractor for QueryMethod {
+    fn extract(&self, query: &Query, answers: &[Record]) -> Result<IpAddr, crate::Error> {
+        match self {
+            Self::A => answers
+                .iter()
+                .find_map(|answer| match answer.data() {
+                    RData::A(addr) => Some(IpAddr::V4(addr.0)),
+                    _ => None,
+                })
+                .ok_or(crate::Error::Addr),
+            Self::AAAA => answers
+                .iter()
+                .find_map(|answer| match answer.data() {
+                    RData::AAAA(addr) => Some(IpAddr::V6(addr.0)),
+                    _ => None,
+                })
+                .ok_or(crate::Error::Addr),
+            Self::TXT => TxtExtractor::new().extract(query, answers),
+        }
+    }
+}
+
+/// Extracts our IP address from the answers of a DNS response.
+///
+/// The answers have already been validated against the query, with any
+/// `CNAME` records forming a chain from the queried name to the answers.
+pub trait Extractor: fmt::Debug + Send + Sync {
+    /// Extracts our IP address from the answers to a query.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no valid IP address could be extracted.
+    fn extract(&self, query: &Query, answers: &[Record]) -> Result<IpAddr, crate::Error>;
+}
+
+/// Configurable extractor for IP addresses within `TXT` character-strings.
+///
+/// Each character-string is trimmed (or split on whitespace if enabled),
+/// candidates not starting with the prefix are skipped and the prefix is
+/// stripped from the rest. Without an index, the first candidate that is a
+/// valid IP address is extracted.
+#[derive(Debug, Clone, Copy)]
+pub struct TxtExtractor<'a> {
+    prefix: &'a str,
+    index: Option<usize>,
+    split_whitespace: bool,
+}
+
+impl<'a> TxtExtractor<'a> {
+    /// Create a new `TXT` extractor.
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            prefix: "",
+            index: None,
+            split_whitespace: false,
+        }
+    }
+
+    /// Sets the prefix candidates must start with, such as `ip=`.
+    #[must_use]
+    pub const fn with_prefix(mut self, prefix: &'a str) -> Self {
+        self.prefix = prefix;
+        self
+    }
+
+    /// Sets the index of the candidate to extract.
+    #[must_use]
+    pub const fn with_index(mut self, index: usize) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Sets whether character-strings are split on whitespace into multiple
+    /// candidates.
+    #[must_use]
+    pub const fn with_split_whitespace(mut self, split_whitespace: bool) -> Self {
+        self.split_whitespace = split_whitespace;
+        self
+    }
+}
+
+impl Default for TxtExtractor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Extractor for TxtExtractor<'_> {
+    fn extract(&self, _query: &Query, answers: &[Record]) -> Result<IpAddr, crate::Error> {
+        let mut candidates = Vec::new();
+        for txt in txt_strings(answers).filter_map(|bytes| str::from_utf8(bytes).ok()) {
+            if self.split_whitespace {
+                candidates.extend(txt.split_whitespace());
+            } else {
+                candidates.push(txt.trim());
+            }
+        }
+        let mut candidates = candidates
+            .into_iter()
+            .filter_map(|candidate| candidate.strip_prefix(self.prefix));
+        match self.index {
+            Some(index) => Ok(candidates.nth(index).ok_or(crate::Error::Addr)?.parse()?),
+            None => candidates
+                .find_map(|candidate| candidate.parse().ok())
+                .ok_or(crate::Error::Addr),
+        }
+    }
+}
+
+/// Transport used to send queries to a DNS server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Transport<'r> {
+    /// Plain DNS over UDP.
+    ///
+    /// Truncated responses are retried over TCP.
+    Udp,
+    /// Plain DNS over TCP.
+    Tcp,
+    /// DNS-over-TLS ([RFC 7858](https://www.rfc-editor.org/rfc/rfc7858)).
+    ///
+    /// The server's certificate is verified against the provided name.
+    /// Requires the `dns-over-tls` feature.
+    Tls(&'r str),
+}
+
+impl Transport<'_> {
+    #[cfg_attr(feature = "dns-over-tls", allow(clippy::unnecessary_wraps))]
+    fn protocol(self) -> Result<Protocol, ProtoError> {
+        match self {
+            Self::Udp => Ok(Protocol::Udp),
+            Self::Tcp => Ok(Protocol::Tcp),
+            #[cfg(feature = "dns-over-tls")]
+            Self::Tls(_) => Ok(Protocol::Tls),
+            #[cfg(not(feature = "dns-over-tls"))]
+            Self::Tls(_) => {
+                Err(ProtoErrorKind::Message("dns-over-tls feature is not enabled").into())
+            }
+        }
+    }
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // Resolver
 
@@ -214,6 +532,13 @@ pub struct Resolver<'r> {
     servers: Cow<'r, [IpAddr]>,
     method: QueryMethod,
     class: DNSClass,
+    transport: Transport<'r>,
+    parallel: bool,
+    case_randomization: bool,
+    system_servers: bool,
+    server_names: &'r [&'r str],
+    extractor: Option<&'r dyn Extractor>,
+    client: Option<&'r Client>,
 }
 
 impl<'r> Resolver<'r> {
@@ -229,8 +554,87 @@ impl<'r> Resolver<'r> {
             servers: servers.into(),
             method,
             class,
+            transport: Transport::Udp,
+            parallel: false,
+            case_randomization: false,
+            system_servers: false,
+            server_names: &[],
+            extractor: None,
+            client: None,
         }
     }
+
+    /// Sets the transport used to query the DNS servers (defaults to UDP).
+    #[must_use]
+    pub const fn with_transport(mut self, transport: Transport<'r>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    /// Sets whether all servers are queried concurrently (defaults to `false`).
+    ///
+    /// When enabled, resolutions are produced in the order the servers
+    /// respond, rather than falling back to the next server only once the
+    /// previous one has failed or timed out.
+    #[must_use]
+    pub const fn with_parallel_queries(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// Sets whether the case of the query name is randomized (defaults to `false`).
+    ///
+    /// This is DNS 0x20 encoding, where a response is only accepted if the
+    /// case of its question section matches the query exactly. This makes
+    /// responses harder to spoof off-path. Not all servers preserve the case
+    /// of the query name, so this should only be enabled for those that do.
+    #[must_use]
+    pub const fn with_case_randomization(mut self, case_randomization: bool) -> Self {
+        self.case_randomization = case_randomization;
+        self
+    }
+
+    /// Sets whether the nameservers from the system's DNS configuration are
+    /// queried instead of the provided servers and port (defaults to `false`).
+    ///
+    /// Requires the `system-config` feature.
+    #[must_use]
+    pub const fn with_system_servers(mut self, system_servers: bool) -> Self {
+        self.system_servers = system_servers;
+        self
+    }
+
+    /// Sets the hostnames of the servers to query.
+    ///
+    /// The hostnames are resolved with the system's resolver at query time,
+    /// so a DNS provider renumbering its servers doesn't go unnoticed. The
+    /// provided server addresses are only queried as a fallback when none of
+    /// the hostnames resolve.
+    #[must_use]
+    pub const fn with_server_names(mut self, server_names: &'r [&'r str]) -> Self {
+        self.server_names = server_names;
+        self
+    }
+
+    /// Sets the extractor used to extract our IP address from the answers
+    /// (defaults to the query method's extraction).
+    ///
+    /// The query method still determines the record type queried.
+    #[must_use]
+    pub const fn with_extractor(mut self, extractor: &'r dyn Extractor) -> Self {
+        self.extractor = Some(extractor);
+        self
+    }
+
+    /// Sets the client used to query the DNS servers.
+    ///
+    /// By default a connection is opened for each query and closed once it
+    /// completes. A shared client keeps connections open across queries.
+    #[must_use]
+    pub const fn with_client(mut self, client: &'r Client) -> Self {
+        self.client = Some(client);
+        self
+    }
 }
 
 impl Resolver<'static> {
@@ -249,6 +653,13 @@ impl Resolver<'static> {
             servers: Cow::Borrowed(servers),
             method,
             class,
+            transport: Transport::Udp,
+            parallel: false,
+            case_randomization: false,
+            system_servers: false,
+            server_names: &[],
+            extractor: None,
+            client: None,
         }
     }
 }
@@ -257,36 +668,56 @@ impl<'r> crate::Resolver<'r> for Resolver<'r> {
     fn resolve(&self, version: Version) -> Resolutions<'r> {
         let port = self.port;
         let method = self.method;
-        let name = match Name::from_ascii(self.name.as_ref()) {
+        let transport = self.transport;
+        let mut name = match Name::from_ascii(self.name.as_ref()) {
             Ok(name) => name,
             Err(err) => return Box::pin(stream::once(future::ready(Err(crate::Error::new(err))))),
         };
-        let mut servers: Vec<_> = self
-            .servers
-            .iter()
-            .copied()
-            .filter(|addr| version.matches(*addr))
-            .collect();
-        let Some(first_server) = servers.pop() else {
-            return Box::pin(stream::empty());
+        // Names decoded from responses are always fully qualified, and only
+        // compare equal to fully qualified names.
+        name.set_fqdn(true);
+        let servers = if self.system_servers {
+            match system_servers() {
+                Ok(servers) => servers,
+                Err(err) => return Box::pin(stream::once(future::ready(Err(err)))),
+            }
+        } else {
+            self.servers
+                .iter()
+                .map(|addr| SocketAddr::new(*addr, port))
+                .collect()
         };
+        let servers: Vec<_> = servers
+            .into_iter()
+            .filter(|addr| version.matches(addr.ip()))
+            .collect();
         let record_type = match self.method {
             QueryMethod::A => RecordType::A,
             QueryMethod::AAAA => RecordType::AAAA,
             QueryMethod::TXT => RecordType::TXT,
         };
-        let span = trace_span!("dns resolver", ?version, ?method, %name, %port);
+        let parallel = self.parallel;
+        let span =
+            trace_span!("dns resolver", ?version, ?method, ?transport, %name, %port, %parallel);
         let mut query = Query::query(name, record_type);
         query.set_query_class(self.class);
-        let stream = resolve(first_server, port, query.clone(), method);
-        let resolutions = DnsResolutions {
-            port,
-            version,
+        let mut options = DnsRequestOptions::default();
+        options.use_edns = true;
+        options.case_randomization = self.case_randomization;
+        let query = DnsQuery {
             query,
+            options,
             method,
-            servers,
-            stream,
+            transport,
+            extractor: self.extractor,
+            client: self.client,
         };
+        if self.system_servers || self.server_names.is_empty() {
+            return Box::pin(query_servers(servers, query, parallel).instrument(span));
+        }
+        let server_names = self.server_names;
+        let resolutions = stream::once(lookup_servers(server_names, port, version, servers))
+            .flat_map(move |servers| query_servers(servers, query.clone(), parallel));
         Box::pin(resolutions.instrument(span))
     }
 }
@@ -294,18 +725,47 @@ impl<'r> crate::Resolver<'r> for Resolver<'r> {
 ///////////////////////////////////////////////////////////////////////////////
 // Resolutions
 
+#[derive(Debug, Clone)]
+struct DnsQuery<'r> {
+    query: Query,
+    options: DnsRequestOptions,
+    method: QueryMethod,
+    transport: Transport<'r>,
+    extractor: Option<&'r dyn Extractor>,
+    client: Option<&'r Client>,
+}
+
 pin_project! {
     struct DnsResolutions<'r> {
-        port: u16,
-        version: Version,
-        query: Query,
-        method: QueryMethod,
-        servers: Vec<IpAddr>,
+        query: DnsQuery<'r>,
+        servers: Vec<SocketAddr>,
         #[pin]
         stream: Resolutions<'r>,
     }
 }
 
+fn query_servers(
+    mut servers: Vec<SocketAddr>,
+    query: DnsQuery<'_>,
+    parallel: bool,
+) -> Resolutions<'_> {
+    if parallel {
+        let streams = servers
+            .into_iter()
+            .map(|server| resolve(server, query.clone()));
+        return Box::pin(stream::select_all(streams));
+    }
+    let Some(first_server) = servers.pop() else {
+        return Box::pin(stream::empty());
+    };
+    let stream = resolve(first_server, query.clone());
+    Box::pin(DnsResolutions {
+        query,
+        servers,
+        stream,
+    })
+}
+
 impl Stream for DnsResolutions<'_> {
     type Item = Result<(IpAddr, crate::Details), crate::Error>;
 
@@ -313,64 +773,533 @@ impl Stream for DnsResolutions<'_> {
         match ready!(self.as_mut().project().stream.poll_next(cx)) {
             Some(o) => Poll::Ready(Some(o)),
             None => self.servers.pop().map_or(Poll::Ready(None), |server| {
-                self.stream = resolve(server, self.port, self.query.clone(), self.method);
+                self.stream = resolve(server, self.query.clone());
                 self.project().stream.poll_next(cx)
             }),
         }
     }
 }
 
+///////////////////////////////////////////////////////////////////////////////
+// Interception detection
+
+/// The outcome of a single probe within an [`Interception`] check.
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "google", feature = "cloudflare", feature = "system-config")))
+)]
+pub type Probe = Result<(IpAddr, Details), crate::Error>;
+
+/// Report produced by [`detect_interception`].
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "google", feature = "cloudflare", feature = "system-config")))
+)]
+#[derive(Debug)]
+pub struct Interception {
+    google_direct: Probe,
+    google_system: Probe,
+    cloudflare_direct: Probe,
+    cloudflare_system: Probe,
+}
+
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+impl Interception {
+    /// Google's `o-o.myaddr.l.google.com` queried directly at [`GOOGLE`].
+    pub fn google_direct(&self) -> &Probe {
+        &self.google_direct
+    }
+
+    /// Google's `o-o.myaddr.l.google.com` queried through the system's
+    /// resolver, producing the resolver's egress IP address.
+    pub fn google_system(&self) -> &Probe {
+        &self.google_system
+    }
+
+    /// Cloudflare's `whoami.cloudflare` queried directly at [`CLOUDFLARE`].
+    pub fn cloudflare_direct(&self) -> &Probe {
+        &self.cloudflare_direct
+    }
+
+    /// Cloudflare's `whoami.cloudflare` queried through the system's resolver.
+    ///
+    /// This is only expected to succeed if the system's resolver is
+    /// Cloudflare's, or forwards queries to Cloudflare.
+    pub fn cloudflare_system(&self) -> &Probe {
+        &self.cloudflare_system
+    }
+
+    /// Returns `true` if queries sent directly to Google's or Cloudflare's
+    /// servers were answered by something else on-path.
+    ///
+    /// Any of the following is considered evidence of interception:
+    ///
+    /// - Google's authoritative servers answer without the authoritative flag
+    ///   set, or either operator answers with an error response code, as only
+    ///   a resolver impersonating them would.
+    /// - Google reports the same address for the direct query as for the
+    ///   query through the system's resolver, meaning the direct query was
+    ///   forwarded through the system's resolver. This is only considered
+    ///   evidence when Cloudflare reports a different address as our own, as
+    ///   a recursive resolver on our own network shares our address.
+    ///
+    /// Probes that failed to get any response (for example when offline) are
+    /// not considered evidence either way. The probe through the system's
+    /// resolver for `whoami.cloudflare` isn't considered either, as a system
+    /// resolver may well forward queries to Cloudflare.
+    #[must_use]
+    pub fn is_intercepted(&self) -> bool {
+        let google_intercepted = match &self.google_direct {
+            Ok((addr, details)) => {
+                let forwarded = matches!(&self.google_system, Ok((egress, _)) if egress == addr)
+                    && matches!(&self.cloudflare_direct, Ok((own, _)) if own != addr);
+                !details.authoritative() || forwarded
+            }
+            Err(err) => is_response_code_error(err),
+        };
+        let cloudflare_intercepted = match &self.cloudflare_direct {
+            Ok(_) => false,
+            Err(err) => is_response_code_error(err),
+        };
+        google_intercepted || cloudflare_intercepted
+    }
+}
+
+/// Detects whether an on-path transparent DNS proxy is intercepting queries
+/// to port 53.
+///
+/// The `o-o.myaddr.l.google.com` and `whoami.cloudflare` queries are sent
+/// both directly to [`GOOGLE`] and [`CLOUDFLARE`], and through the system's
+/// configured resolver, with all probes run concurrently.
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+#[cfg_attr(
+    docsrs,
+    doc(cfg(all(feature = "google", feature = "cloudflare", feature = "system-config")))
+)]
+pub async fn detect_interception(version: Version) -> Interception {
+    const CLOUDFLARE_RECURSIVE: &dyn crate::Resolver<'static> = &Resolver::new_static(
+        "whoami.cloudflare",
+        &[],
+        DEFAULT_DNS_PORT,
+        QueryMethod::TXT,
+        DNSClass::CH,
+    )
+    .with_system_servers(true);
+
+    let (google_direct, google_system, cloudflare_direct, cloudflare_system) = future::join4(
+        probe(GOOGLE, version),
+        probe(GOOGLE_RECURSIVE, version),
+        probe(CLOUDFLARE, version),
+        probe(CLOUDFLARE_RECURSIVE, version),
+    )
+    .await;
+    Interception {
+        google_direct,
+        google_system,
+        cloudflare_direct,
+        cloudflare_system,
+    }
+}
+
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+fn is_response_code_error(err: &crate::Error) -> bool {
+    matches!(err, crate::Error::Dns(Error::ResponseCode(_)))
+}
+
+/// Produces the first successful resolution, or the last error.
+#[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+async fn probe(resolver: &'static dyn crate::Resolver<'static>, version: Version) -> Probe {
+    let mut resolutions = crate::resolve(resolver, version);
+    let mut last_err = crate::Error::Addr;
+    while let Some(result) = resolutions.next().await {
+        match result {
+            Ok((addr, details)) => {
+                let details = details.downcast::<Details>().map_err(|_| {
+                    Error::from(ProtoError::from(ProtoErrorKind::Message(
+                        "resolution did not produce DNS details",
+                    )))
+                })?;
+                return Ok((addr, *details));
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // Client
 
+/// A reusable DNS client that keeps connections to DNS servers open across
+/// queries.
+///
+/// Resolvers configured with the same client through
+/// [`Resolver::with_client`] share its connections. The background tasks
+/// driving the connections are aborted when the client is closed or dropped.
+#[derive(Default)]
+pub struct Client {
+    connections: Mutex<HashMap<ConnectionKey, Connection>>,
+}
+
+impl Client {
+    /// Create a new DNS client without any open connections.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Closes all open connections.
+    ///
+    /// Connections are reopened as required by any further queries.
+    pub fn close(&self) {
+        self.connections().clear();
+    }
+
+    fn connections(&self) -> MutexGuard<'_, HashMap<ConnectionKey, Connection>> {
+        self.connections
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "tokio-dns-resolver")]
+    async fn lookup(
+        &self,
+        server: SocketAddr,
+        transport: Transport<'_>,
+        query: Query,
+        query_opts: DnsRequestOptions,
+    ) -> Result<(DnsResponse, Duration), ProtoError> {
+        let key = ConnectionKey::new(server, transport)?;
+        let client = self
+            .connections()
+            .get(&key)
+            .filter(|connection| !connection.background.is_finished())
+            .map(|connection| connection.client.clone());
+        let client = if let Some(client) = client {
+            client
+        } else {
+            let connection = Connection::connect(server, transport).await?;
+            let mut connections = self.connections();
+            // Keep a connection opened by a concurrent query, as it may still
+            // be in use.
+            if let Some(existing) = connections
+                .get(&key)
+                .filter(|existing| !existing.background.is_finished())
+            {
+                existing.client.clone()
+            } else {
+                let client = connection.client.clone();
+                connections.insert(key.clone(), connection);
+                client
+            }
+        };
+        let result = dns_lookup(&client, query, query_opts).await;
+        if result.is_err() {
+            // A failed exchange may leave the connection unusable, so the
+            // next query opens a fresh one.
+            self.connections().remove(&key);
+        }
+        result
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("connections", &self.connections().len())
+            .finish()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ConnectionKey {
+    server: SocketAddr,
+    protocol: Protocol,
+    tls_name: Option<String>,
+}
+
+impl ConnectionKey {
+    fn new(server: SocketAddr, transport: Transport<'_>) -> Result<Self, ProtoError> {
+        let tls_name = match transport {
+            Transport::Tls(server_name) => Some(server_name.to_owned()),
+            Transport::Udp | Transport::Tcp => None,
+        };
+        Ok(Self {
+            server,
+            protocol: transport.protocol()?,
+            tls_name,
+        })
+    }
+}
+
+/// An open connection, aborting its background task once dropped.
+struct Connection {
+    client: DnsClient,
+    background: JoinHandle<Result<(), ProtoError>>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.background.abort();
+    }
+}
+
+#[cfg(feature = "tokio-dns-resolver")]
+impl Connection {
+    async fn connect(server: SocketAddr, transport: Transport<'_>) -> Result<Self, ProtoError> {
+        let handle = Handle::current();
+        let provider = TokioRuntimeProvider::new();
+        let (client, background) = match transport {
+            Transport::Udp => {
+                let stream = UdpClientStream::builder(server, provider).build();
+                let (client, bg) = DnsClient::connect(stream).await?;
+                (client, handle.spawn(bg))
+            }
+            Transport::Tcp => {
+                let (stream, sender) = TcpClientStream::new(server, None, None, provider);
+                let (client, bg) = DnsClient::new(stream, sender, None).await?;
+                (client, handle.spawn(bg))
+            }
+            #[cfg(feature = "dns-over-tls")]
+            Transport::Tls(server_name) => {
+                let (stream, sender) = tls_client_connect(
+                    server,
+                    server_name.to_owned(),
+                    tls_client_config(),
+                    provider,
+                );
+                let (client, bg) = DnsClient::new(stream, sender, None).await?;
+                (client, handle.spawn(bg))
+            }
+            #[cfg(not(feature = "dns-over-tls"))]
+            Transport::Tls(_) => {
+                return Err(ProtoErrorKind::Message("dns-over-tls feature is not enabled").into());
+            }
+        };
+        Ok(Self { client, background })
+    }
+}
+
 #[cfg(feature = "tokio-dns-resolver")]
 async fn dns_query(
+    server: SocketAddr,
+    mut query: Query,
+    mut query_opts: DnsRequestOptions,
+    transport: Transport<'_>,
+    client: Option<&Client>,
+) -> Result<(DnsResponse, Protocol, Duration), Error> {
+    // The case is randomized here rather than by hickory, which only checks
+    // the case of UDP responses.
+    let case_randomization = query_opts.case_randomization;
+    if case_randomization {
+        let mut name = query.name().clone();
+        name.randomize_label_case();
+        query.set_name(name);
+        query_opts.case_randomization = false;
+    }
+    let (mut response, mut latency) =
+        dns_exchange(server, query.clone(), query_opts, transport, client).await?;
+    let mut protocol = transport.protocol()?;
+    // A truncated UDP response is retried over TCP (RFC 7766).
+    if transport == Transport::Udp && response.truncated() {
+        tracing::trace!("response truncated, retrying over tcp");
+        (response, latency) =
+            dns_exchange(server, query.clone(), query_opts, Transport::Tcp, client).await?;
+        protocol = Protocol::Tcp;
+    }
+    if case_randomization
+        && !response
+            .queries()
+            .iter()
+            .all(|response_query| response_query.name().eq_case(query.name()))
+    {
+        return Err(ProtoError::from(ProtoErrorKind::QueryCaseMismatch).into());
+    }
+    Ok((response, protocol, latency))
+}
+
+#[cfg(feature = "tokio-dns-resolver")]
+async fn dns_exchange(
     server: SocketAddr,
     query: Query,
     query_opts: DnsRequestOptions,
-) -> Result<DnsResponse, ProtoError> {
-    let handle = Handle::current();
-    let stream = UdpClientStream::builder(server, TokioRuntimeProvider::new()).build();
-    let (client, bg) = Client::connect(stream).await?;
-    handle.spawn(bg);
-    client
+    transport: Transport<'_>,
+    client: Option<&Client>,
+) -> Result<(DnsResponse, Duration), ProtoError> {
+    if let Some(client) = client {
+        return client.lookup(server, transport, query, query_opts).await;
+    }
+    // The connection is closed once the query completes.
+    let connection = Connection::connect(server, transport).await?;
+    dns_lookup(&connection.client, query, query_opts).await
+}
+
+#[cfg(feature = "tokio-dns-resolver")]
+async fn dns_lookup(
+    client: &DnsClient,
+    query: Query,
+    query_opts: DnsRequestOptions,
+) -> Result<(DnsResponse, Duration), ProtoError> {
+    let start = Instant::now();
+    let response = client
         .lookup(query, query_opts)
         .next()
         .await
         .transpose()?
-        .ok_or_else(|| ProtoErrorKind::Message("expected a response").into())
+        .ok_or_else(|| ProtoError::from(ProtoErrorKind::Message("expected a response")))?;
+    Ok((response, start.elapsed()))
 }
 
-fn parse_dns_response(
-    mut response: DnsResponse,
-    method: QueryMethod,
-) -> Result<IpAddr, crate::Error> {
-    let Some(answer) = response.take_answers().into_iter().next() else {
-        return Err(crate::Error::Addr);
-    };
-    match answer.into_data() {
-        RData::A(addr) if method == QueryMethod::A => Ok(IpAddr::V4(addr.0)),
-        RData::AAAA(addr) if method == QueryMethod::AAAA => Ok(IpAddr::V6(addr.0)),
-        RData::TXT(txt) if method == QueryMethod::TXT => match txt.iter().next() {
-            Some(addr_bytes) => Ok(str::from_utf8(&addr_bytes[..])?.parse()?),
-            None => Err(crate::Error::Addr),
-        },
-        _ => Err(ProtoError::from(ProtoErrorKind::Message("invalid response")).into()),
+/// Resolves server hostnames, falling back to the provided bootstrap servers.
+#[cfg(feature = "tokio-dns-resolver")]
+async fn lookup_servers(
+    server_names: &[&str],
+    port: u16,
+    version: Version,
+    bootstrap: Vec<SocketAddr>,
+) -> Vec<SocketAddr> {
+    let mut servers = Vec::new();
+    for server_name in server_names {
+        match tokio::net::lookup_host((*server_name, port)).await {
+            Ok(addrs) => {
+                for addr in addrs {
+                    if version.matches(addr.ip()) && !servers.contains(&addr) {
+                        servers.push(addr);
+                    }
+                }
+            }
+            Err(err) => tracing::trace!(%server_name, %err, "failed to resolve server name"),
+        }
     }
+    if servers.is_empty() {
+        tracing::trace!("no server names resolved, using bootstrap servers");
+        return bootstrap;
+    }
+    servers
 }
 
-fn resolve<'r>(server: IpAddr, port: u16, query: Query, method: QueryMethod) -> Resolutions<'r> {
+#[cfg(feature = "system-config")]
+fn system_servers() -> Result<Vec<SocketAddr>, crate::Error> {
+    let (config, _) =
+        hickory_resolver::system_conf::read_system_conf().map_err(crate::Error::new)?;
+    let mut servers = Vec::new();
+    for name_server in config.name_servers() {
+        if !servers.contains(&name_server.socket_addr) {
+            servers.push(name_server.socket_addr);
+        }
+    }
+    Ok(servers)
+}
+
+#[cfg(not(feature = "system-config"))]
+fn system_servers() -> Result<Vec<SocketAddr>, crate::Error> {
+    Err(Error::from(ProtoError::from(ProtoErrorKind::Message(
+        "system-config feature is not enabled",
+    )))
+    .into())
+}
+
+#[cfg(feature = "dns-over-tls")]
+fn tls_client_config() -> Arc<rustls::ClientConfig> {
+    static CONFIG: OnceLock<Arc<rustls::ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| Arc::new(hickory_proto::rustls::client_config()))
+        .clone()
+}
+
+/// Validates a response strictly against the query it answers.
+///
+/// UDP responses from an address other than the queried server are
+/// discarded by the transport before they reach this point.
+fn validate_dns_response(response: &DnsResponse, query: &Query) -> Result<(), Error> {
+    let response_code = response.response_code();
+    if response_code != ResponseCode::NoError {
+        return Err(Error::ResponseCode(response_code));
+    }
+    if response.queries() != slice::from_ref(query) {
+        return Err(Error::QueryMismatch);
+    }
+    // Follow any CNAME chain from the queried name to the answers.
+    let mut name = query.name();
+    let mut chain = vec![name];
+    if query.query_type() != RecordType::CNAME {
+        while let Some(target) = response
+            .answers()
+            .iter()
+            .find_map(|answer| match answer.data() {
+                RData::CNAME(cname) if answer.name() == name => Some(&cname.0),
+                _ => None,
+            })
+        {
+            // Stop at CNAME loops.
+            if chain.contains(&target) {
+                break;
+            }
+            chain.push(target);
+            name = target;
+        }
+    }
+    for answer in response.answers() {
+        if answer.dns_class() != query.query_class() {
+            return Err(Error::UnexpectedClass(answer.dns_class()));
+        }
+        if answer.record_type() == RecordType::CNAME && query.query_type() != RecordType::CNAME {
+            if !chain.contains(&answer.name()) {
+                return Err(Error::UnexpectedName(Box::new(answer.name().clone())));
+            }
+            continue;
+        }
+        if answer.name() != name {
+            return Err(Error::UnexpectedName(Box::new(answer.name().clone())));
+        }
+        if answer.record_type() != query.query_type() {
+            return Err(Error::UnexpectedType(answer.record_type()));
+        }
+    }
+    Ok(())
+}
+
+fn txt_strings(answers: &[Record]) -> impl Iterator<Item = &[u8]> {
+    answers
+        .iter()
+        .filter_map(|answer| match answer.data() {
+            RData::TXT(txt) => Some(txt.iter()),
+            _ => None,
+        })
+        .flatten()
+        .map(AsRef::as_ref)
+}
+
+fn resolve(server: SocketAddr, query: DnsQuery<'_>) -> Resolutions<'_> {
+    let DnsQuery {
+        query,
+        options,
+        method,
+        transport,
+        extractor,
+        client,
+    } = query;
     let fut = async move {
         let name = query.name().clone();
-        let server = SocketAddr::new(server, port);
-        let mut query_opts = DnsRequestOptions::default();
-        query_opts.use_edns = true;
-        let response = dns_query(server, query, query_opts).await?;
-        let addr = parse_dns_response(response, method)?;
+        let (mut response, protocol, latency) =
+            dns_query(server, query.clone(), options, transport, client).await?;
+        validate_dns_response(&response, &query)?;
+        let addr = match extractor {
+            Some(extractor) => extractor.extract(&query, response.answers())?,
+            None => method.extract(&query, response.answers())?,
+        };
         let details = Box::new(Details {
             name,
             server,
             method,
+            protocol,
+            latency,
+            response_code: response.response_code(),
+            authoritative: response.authoritative(),
+            recursion_available: response.recursion_available(),
+            answers: response.take_answers(),
         });
         Ok((addr, crate::Details::from(details)))
     };
@@ -378,3 +1307,525 @@ fn resolve<'r>(server: IpAddr, port: u16, query: Query, method: QueryMethod) ->
         fut.instrument(trace_span!("query server", %server)),
     ))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use hickory_proto::op::{Message, MessageType};
+    use hickory_proto::rr::rdata::{CNAME, TXT};
+
+    use super::*;
+
+    fn txt_query(name: &str) -> Query {
+        let mut query = Query::query(Name::from_ascii(name).unwrap(), RecordType::TXT);
+        query.set_query_class(DNSClass::CH);
+        query
+    }
+
+    fn txt_response(query: &Query, answer: Record) -> DnsResponse {
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.add_answer(answer);
+        DnsResponse::from_message(message).unwrap()
+    }
+
+    fn txt_record(name: &str, class: DNSClass) -> Record {
+        txt_record_with(name, class, "1.2.3.4")
+    }
+
+    fn txt_record_with(name: &str, class: DNSClass, txt: &str) -> Record {
+        let mut record = Record::from_rdata(
+            Name::from_ascii(name).unwrap(),
+            0,
+            RData::TXT(TXT::new(vec![txt.to_owned()])),
+        );
+        record.set_dns_class(class);
+        record
+    }
+
+    #[test]
+    fn test_validate_dns_response() {
+        let query = txt_query("whoami.cloudflare");
+
+        let valid = txt_response(&query, txt_record("WHOAMI.cloudflare", DNSClass::CH));
+        assert!(validate_dns_response(&valid, &query).is_ok());
+        assert_eq!(
+            QueryMethod::TXT.extract(&query, valid.answers()).unwrap(),
+            IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4))
+        );
+
+        let wrong_name = txt_response(&query, txt_record("evil.example", DNSClass::CH));
+        assert!(matches!(
+            validate_dns_response(&wrong_name, &query).unwrap_err(),
+            Error::UnexpectedName(_)
+        ));
+
+        let wrong_class = txt_response(&query, txt_record("whoami.cloudflare", DNSClass::IN));
+        assert!(matches!(
+            validate_dns_response(&wrong_class, &query).unwrap_err(),
+            Error::UnexpectedClass(DNSClass::IN)
+        ));
+
+        let other_query = txt_query("evil.example");
+        let wrong_query = txt_response(&other_query, txt_record("whoami.cloudflare", DNSClass::CH));
+        assert!(matches!(
+            validate_dns_response(&wrong_query, &query).unwrap_err(),
+            Error::QueryMismatch
+        ));
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.set_response_code(ResponseCode::Refused);
+        let refused = DnsResponse::from_message(message).unwrap();
+        assert!(matches!(
+            validate_dns_response(&refused, &query).unwrap_err(),
+            Error::ResponseCode(ResponseCode::Refused)
+        ));
+    }
+
+    #[test]
+    fn test_parse_google_txt() {
+        let mut query = Query::query(
+            Name::from_ascii("o-o.myaddr.l.google.com").unwrap(),
+            RecordType::TXT,
+        );
+        query.set_query_class(DNSClass::IN);
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.add_answer(txt_record_with(
+            "o-o.myaddr.l.google.com",
+            DNSClass::IN,
+            "edns0-client-subnet 203.0.113.0/24",
+        ));
+        message.add_answer(txt_record_with(
+            "o-o.myaddr.l.google.com",
+            DNSClass::IN,
+            "198.51.100.1",
+        ));
+        let response = DnsResponse::from_message(message).unwrap();
+        assert!(validate_dns_response(&response, &query).is_ok());
+        assert_eq!(
+            QueryMethod::TXT
+                .extract(&query, response.answers())
+                .unwrap(),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))
+        );
+
+        let details = Details {
+            name: query.name().clone(),
+            server: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(216, 239, 32, 10)), 53),
+            method: QueryMethod::TXT,
+            protocol: Protocol::Udp,
+            latency: Duration::ZERO,
+            response_code: response.response_code(),
+            authoritative: response.authoritative(),
+            recursion_available: response.recursion_available(),
+            answers: response.answers().to_vec(),
+        };
+        assert_eq!(details.txt().count(), 2);
+        assert_eq!(
+            details.edns_client_subnet(),
+            Some((IpAddr::V4(Ipv4Addr::new(203, 0, 113, 0)), 24))
+        );
+    }
+
+    #[test]
+    fn test_txt_extractor() {
+        let query = txt_query("whoami.example");
+        let answers = [
+            txt_record_with("whoami.example", DNSClass::CH, "v=1 ip=198.51.100.1"),
+            txt_record_with("whoami.example", DNSClass::CH, "ip=2001:db8::1"),
+        ];
+        let extractor = TxtExtractor::new()
+            .with_prefix("ip=")
+            .with_split_whitespace(true);
+        assert_eq!(
+            extractor.extract(&query, &answers).unwrap(),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))
+        );
+        assert_eq!(
+            extractor.with_index(1).extract(&query, &answers).unwrap(),
+            IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))
+        );
+        assert!(matches!(
+            TxtExtractor::new().extract(&query, &answers).unwrap_err(),
+            crate::Error::Addr
+        ));
+    }
+
+    #[test]
+    fn test_validate_cname_chain() {
+        let query = Query::query(Name::from_ascii("myip.example").unwrap(), RecordType::A);
+        let cname = |name: &str, target: &str| {
+            Record::from_rdata(
+                Name::from_ascii(name).unwrap(),
+                0,
+                RData::CNAME(CNAME(Name::from_ascii(target).unwrap())),
+            )
+        };
+        let a = |name: &str| {
+            Record::from_rdata(
+                Name::from_ascii(name).unwrap(),
+                0,
+                RData::A(Ipv4Addr::new(198, 51, 100, 1).into()),
+            )
+        };
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.add_answer(cname("myip.example", "edge.example"));
+        message.add_answer(a("edge.example"));
+        let chained = DnsResponse::from_message(message).unwrap();
+        assert!(validate_dns_response(&chained, &query).is_ok());
+        assert_eq!(
+            QueryMethod::A.extract(&query, chained.answers()).unwrap(),
+            IpAddr::V4(Ipv4Addr::new(198, 51, 100, 1))
+        );
+
+        let mut message = Message::new();
+        message.add_query(query.clone());
+        message.add_answer(cname("myip.example", "edge.example"));
+        message.add_answer(a("evil.example"));
+        let unchained = DnsResponse::from_message(message).unwrap();
+        assert!(matches!(
+            validate_dns_response(&unchained, &query).unwrap_err(),
+            Error::UnexpectedName(_)
+        ));
+    }
+
+    #[cfg(all(feature = "google", feature = "cloudflare", feature = "system-config"))]
+    #[test]
+    fn test_interception() {
+        let probe = |addr: [u8; 4], server: [u8; 4], authoritative: bool| -> Probe {
+            let details = Details {
+                name: Name::from_ascii("o-o.myaddr.l.google.com").unwrap(),
+                server: SocketAddr::new(IpAddr::from(server), 53),
+                method: QueryMethod::TXT,
+                protocol: Protocol::Udp,
+                latency: Duration::ZERO,
+                response_code: ResponseCode::NoError,
+                authoritative,
+                recursion_available: !authoritative,
+                answers: Vec::new(),
+            };
+            Ok((IpAddr::from(addr), details))
+        };
+        let offline = || -> Probe { Err(crate::Error::Addr) };
+        let refused = || -> Probe { Err(Error::ResponseCode(ResponseCode::Refused).into()) };
+        let own = [198, 51, 100, 1];
+        let egress = [203, 0, 113, 1];
+        let google = [216, 239, 32, 10];
+        let cloudflare = [1, 1, 1, 1];
+        let system = [192, 168, 1, 1];
+
+        let clean = || Interception {
+            google_direct: probe(own, google, true),
+            google_system: probe(egress, system, false),
+            cloudflare_direct: probe(own, cloudflare, false),
+            cloudflare_system: offline(),
+        };
+        assert!(!clean().is_intercepted());
+
+        let unreachable = Interception {
+            google_direct: offline(),
+            google_system: offline(),
+            cloudflare_direct: offline(),
+            cloudflare_system: offline(),
+        };
+        assert!(!unreachable.is_intercepted());
+
+        let not_authoritative = Interception {
+            google_direct: probe(egress, google, false),
+            ..clean()
+        };
+        assert!(not_authoritative.is_intercepted());
+
+        let refused = Interception {
+            cloudflare_direct: refused(),
+            ..clean()
+        };
+        assert!(refused.is_intercepted());
+
+        // A forwarding proxy passing the authoritative flag through.
+        let forwarded = Interception {
+            google_direct: probe(egress, google, true),
+            ..clean()
+        };
+        assert!(forwarded.is_intercepted());
+
+        // A recursive resolver on our own network shares our egress address.
+        let local_resolver = Interception {
+            google_system: probe(own, system, false),
+            ..clean()
+        };
+        assert!(!local_resolver.is_intercepted());
+
+        // Without Cloudflare reporting our own address, a local resolver can't
+        // be told apart from forwarding.
+        let local_resolver_offline = Interception {
+            google_system: probe(own, system, false),
+            cloudflare_direct: offline(),
+            ..clean()
+        };
+        assert!(!local_resolver_offline.is_intercepted());
+
+        // A system resolver forwarding `whoami.cloudflare` to Cloudflare.
+        let cloudflare_upstream = Interception {
+            cloudflare_system: probe(egress, [127, 0, 0, 53], false),
+            ..clean()
+        };
+        assert!(!cloudflare_upstream.is_intercepted());
+
+        let cloudflare_system = Interception {
+            cloudflare_system: probe(own, cloudflare, false),
+            ..clean()
+        };
+        assert!(!cloudflare_system.is_intercepted());
+    }
+
+    /// Answers each query with a `TXT` record of `1.2.3.4`, echoing the
+    /// question section as it was sent.
+    fn answer(request: &Message) -> Message {
+        let mut response = Message::new();
+        response
+            .set_id(request.id())
+            .set_message_type(MessageType::Response)
+            .add_queries(request.queries().to_vec());
+        for query in request.queries() {
+            let mut record = Record::from_rdata(
+                query.name().clone(),
+                0,
+                RData::TXT(TXT::new(vec!["1.2.3.4".to_owned()])),
+            );
+            record.set_dns_class(query.query_class());
+            response.add_answer(record);
+        }
+        response
+    }
+
+    /// Number of TCP connections accepted and closed by a local server.
+    #[derive(Default)]
+    struct Connections {
+        accepted: AtomicUsize,
+        closed: AtomicUsize,
+    }
+
+    async fn serve(handler: fn(&Message, Protocol) -> Message) -> SocketAddr {
+        serve_with(handler, Arc::default()).await
+    }
+
+    /// Serves responses from the handler over both UDP and TCP on the same
+    /// local port.
+    async fn serve_with(
+        handler: fn(&Message, Protocol) -> Message,
+        connections: Arc<Connections>,
+    ) -> SocketAddr {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let socket = tokio::net::UdpSocket::bind(addr).await.unwrap();
+        tokio::spawn(async move {
+            let mut request = [0; 4096];
+            while let Ok((len, src)) = socket.recv_from(&mut request).await {
+                let request = Message::from_vec(&request[..len]).unwrap();
+                let response = handler(&request, Protocol::Udp).to_vec().unwrap();
+                let _ = socket.send_to(&response, src).await;
+            }
+        });
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                connections.accepted.fetch_add(1, Ordering::SeqCst);
+                let connections = connections.clone();
+                tokio::spawn(async move {
+                    // Each message is prefixed with its length.
+                    while let Ok(len) = stream.read_u16().await {
+                        let mut request = vec![0; usize::from(len)];
+                        if stream.read_exact(&mut request).await.is_err() {
+                            break;
+                        }
+                        let request = Message::from_vec(&request).unwrap();
+                        let response = handler(&request, Protocol::Tcp).to_vec().unwrap();
+                        let len = u16::try_from(response.len()).unwrap();
+                        if stream.write_u16(len).await.is_err()
+                            || stream.write_all(&response).await.is_err()
+                        {
+                            break;
+                        }
+                    }
+                    connections.closed.fetch_add(1, Ordering::SeqCst);
+                });
+            }
+        });
+        addr
+    }
+
+    fn local_resolver(server: SocketAddr) -> Resolver<'static> {
+        Resolver::new(
+            "whoami.example",
+            vec![server.ip()],
+            server.port(),
+            QueryMethod::TXT,
+            DNSClass::CH,
+        )
+    }
+
+    async fn resolve_with(resolver: Resolver<'_>) -> Result<(IpAddr, Details), crate::Error> {
+        let (addr, details) = crate::resolve(resolver, Version::Any)
+            .next()
+            .await
+            .unwrap()?;
+        Ok((addr, *details.downcast::<Details>().unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_local_server() {
+        let server = serve(|request, _| answer(request)).await;
+        for transport in [Transport::Udp, Transport::Tcp] {
+            let resolver = local_resolver(server).with_transport(transport);
+            let (addr, details) = resolve_with(resolver).await.unwrap();
+            assert_eq!(addr, IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+            assert_eq!(details.server(), server);
+            assert_eq!(details.protocol(), transport.protocol().unwrap());
+            assert_eq!(details.name().to_ascii(), "whoami.example.");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_case_randomization() {
+        let preserved = serve(|request, _| answer(request)).await;
+        // A server answering with the case of every letter flipped, so the
+        // case never matches the query.
+        let flipped = serve(|request, _| {
+            let mut request = request.clone();
+            for query in request.queries_mut() {
+                let name: String = query
+                    .name()
+                    .to_ascii()
+                    .chars()
+                    .map(|c| match c {
+                        'a'..='z' => c.to_ascii_uppercase(),
+                        _ => c.to_ascii_lowercase(),
+                    })
+                    .collect();
+                query.set_name(Name::from_ascii(name).unwrap());
+            }
+            answer(&request)
+        })
+        .await;
+        for transport in [Transport::Udp, Transport::Tcp] {
+            let resolver = local_resolver(preserved)
+                .with_transport(transport)
+                .with_case_randomization(true);
+            assert!(resolve_with(resolver).await.is_ok());
+
+            let resolver = local_resolver(flipped)
+                .with_transport(transport)
+                .with_case_randomization(true);
+            let err = resolve_with(resolver).await.unwrap_err();
+            assert!(
+                matches!(&err, crate::Error::Dns(Error::Proto(err)) if matches!(err.kind(), ProtoErrorKind::QueryCaseMismatch)),
+                "{err}"
+            );
+
+            let resolver = local_resolver(flipped).with_transport(transport);
+            assert!(resolve_with(resolver).await.is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncated_response() {
+        let server = serve(|request, protocol| {
+            let mut response = answer(request);
+            if protocol == Protocol::Udp {
+                response.take_answers();
+                response.set_truncated(true);
+            }
+            response
+        })
+        .await;
+        let (addr, details) = resolve_with(local_resolver(server)).await.unwrap();
+        assert_eq!(addr, IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(details.protocol(), Protocol::Tcp);
+    }
+
+    #[tokio::test]
+    async fn test_server_names() {
+        let server = serve(|request, _| answer(request)).await;
+
+        // The bootstrap servers are used when none of the names resolve.
+        let resolver = local_resolver(server).with_server_names(&["dns.invalid"]);
+        let (_, details) = resolve_with(resolver).await.unwrap();
+        assert_eq!(details.server(), server);
+
+        // The bootstrap servers are unused when a name resolves.
+        let resolver = Resolver::new(
+            "whoami.example",
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))],
+            server.port(),
+            QueryMethod::TXT,
+            DNSClass::CH,
+        )
+        .with_server_names(&["dns.invalid", "localhost"]);
+        let (addr, details) = crate::resolve(resolver, Version::V4)
+            .next()
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(addr, IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)));
+        assert_eq!(details.downcast::<Details>().unwrap().server(), server);
+    }
+
+    #[tokio::test]
+    async fn test_client() {
+        let connections = Arc::new(Connections::default());
+        let server = serve_with(|request, _| answer(request), connections.clone()).await;
+        let closed = |count| {
+            let connections = connections.clone();
+            tokio::time::timeout(Duration::from_secs(5), async move {
+                while connections.closed.load(Ordering::SeqCst) < count {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+        };
+        let client = Client::new();
+        let resolver = || {
+            local_resolver(server)
+                .with_transport(Transport::Tcp)
+                .with_client(&client)
+        };
+        for _ in 0..2 {
+            resolve_with(resolver()).await.unwrap();
+        }
+        assert_eq!(connections.accepted.load(Ordering::SeqCst), 1);
+
+        // Closing the client aborts the background task, closing the
+        // connection, and the next query opens a new one.
+        client.close();
+        closed(1).await.unwrap();
+        resolve_with(resolver()).await.unwrap();
+        assert_eq!(connections.accepted.load(Ordering::SeqCst), 2);
+
+        drop(client);
+        closed(2).await.unwrap();
+    }
+
+    #[cfg(not(feature = "dns-over-tls"))]
+    #[tokio::test]
+    async fn test_tls_transport_without_feature() {
+        let client = Client::new();
+        let resolver = Resolver::new(
+            "whoami.example",
+            vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))],
+            853,
+            QueryMethod::TXT,
+            DNSClass::CH,
+        )
+        .with_transport(Transport::Tls("dns.example"))
+        .with_client(&client);
+        let result = crate::resolve(resolver, Version::Any).next().await;
+        assert!(matches!(result, Some(Err(crate::Error::Dns(_)))));
+    }
+}
@@ -32,10 +32,7 @@
 
 mod error;
 
-#[cfg(any(
-    all(feature = "dns-resolver", not(feature = "tokio-dns-resolver")),
-    all(feature = "http-resolver", not(feature = "tokio-http-resolver"))
-))]
+#[cfg(all(feature = "dns-resolver", not(feature = "tokio-dns-resolver")))]
 compile_error!(
     "tokio is not enabled and is the only supported runtime currently - consider creating a PR or issue"
 );
@@ -47,6 +44,23 @@ compile_error!(
 ))]
 compile_error!("only one of https-openssl/https-rustls-native/https-rustls-webpki can be enabled");
 
+#[cfg(all(
+    feature = "http-resolver",
+    not(feature = "tokio-http-resolver"),
+    any(
+        feature = "https-openssl",
+        feature = "https-rustls-native",
+        feature = "https-rustls-webpki"
+    )
+))]
+compile_error!("https-openssl/https-rustls-native/https-rustls-webpki require tokio-http-resolver");
+
+#[cfg(all(
+    feature = "http3",
+    not(any(feature = "https-rustls-native", feature = "https-rustls-webpki"))
+))]
+compile_error!("http3 requires one of https-rustls-native/https-rustls-webpki to be enabled");
+
 /// DNS resolver support.
 #[cfg(feature = "dns-resolver")]
 #[cfg_attr(docsrs, doc(cfg(feature = "dns-resolver")))]